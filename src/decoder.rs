@@ -0,0 +1,126 @@
+//! Table-driven quadrature decoder.
+//!
+//! Replaces `rotary_encoder_hal::Rotary`, which only looks at the latest
+//! `(a, b)` pair and can't tell a real movement from a bounced or skipped
+//! transition. This tracks the full 4-bit `(previous, current)` state and
+//! looks the transition up in the standard 16-entry table used by most
+//! quadrature decoders, so invalid jumps (both bits changing at once) are
+//! ignored rather than guessed at.
+
+/// `table[(prev << 2) | current]` gives the movement contributed by that
+/// transition: `+1` / `-1` for a valid quarter-step, `0` for no movement or
+/// an invalid (both-bits-changed) transition.
+#[rustfmt::skip]
+const TRANSITION_TABLE: [i8; 16] = [
+    0, -1,  1,  0,
+    1,  0,  0, -1,
+   -1,  0,  0,  1,
+    0,  1, -1,  0,
+];
+
+/// One full detent, in quarter-steps.
+const STEPS_PER_DETENT: i8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotaryDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Full quadrature state machine decoder.
+///
+/// Feed every `(a, b)` reading to [`update`](Self::update), including ones
+/// that don't change anything; the decoder only reports a direction once a
+/// full detent's worth of quarter-steps has accumulated.
+pub struct QuadratureDecoder {
+    state: u8,
+    accumulator: i8,
+    net_steps: i32,
+}
+
+impl QuadratureDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: 0,
+            accumulator: 0,
+            net_steps: 0,
+        }
+    }
+
+    /// Feeds the current `(a, b)` reading through the decoder, returning a
+    /// direction once `STEPS_PER_DETENT` quarter-steps have accumulated in
+    /// one direction since the last detent.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<RotaryDirection> {
+        let current = ((a as u8) << 1) | (b as u8);
+        self.state = ((self.state << 2) | current) & 0b1111;
+        self.accumulator += TRANSITION_TABLE[self.state as usize];
+
+        if self.accumulator >= STEPS_PER_DETENT {
+            self.accumulator -= STEPS_PER_DETENT;
+            self.net_steps += 1;
+            Some(RotaryDirection::Clockwise)
+        } else if self.accumulator <= -STEPS_PER_DETENT {
+            self.accumulator += STEPS_PER_DETENT;
+            self.net_steps -= 1;
+            Some(RotaryDirection::CounterClockwise)
+        } else {
+            None
+        }
+    }
+
+    /// Net number of full detents turned since startup, positive clockwise.
+    pub fn net_steps(&self) -> i32 {
+        self.net_steps
+    }
+}
+
+impl Default for QuadratureDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gray-code sequence for one full clockwise detent: 00 -> 10 -> 11 -> 01 -> 00.
+    const CW_DETENT: [(bool, bool); 4] =
+        [(true, false), (true, true), (false, true), (false, false)];
+
+    /// Mirror image of [`CW_DETENT`]: 00 -> 01 -> 11 -> 10 -> 00.
+    const CCW_DETENT: [(bool, bool); 4] =
+        [(false, true), (true, true), (true, false), (false, false)];
+
+    #[test]
+    fn full_clockwise_detent() {
+        let mut decoder = QuadratureDecoder::new();
+        let mut direction = None;
+        for (a, b) in CW_DETENT {
+            direction = decoder.update(a, b).or(direction);
+        }
+        assert_eq!(direction, Some(RotaryDirection::Clockwise));
+        assert_eq!(decoder.net_steps(), 1);
+    }
+
+    #[test]
+    fn full_counter_clockwise_detent() {
+        let mut decoder = QuadratureDecoder::new();
+        let mut direction = None;
+        for (a, b) in CCW_DETENT {
+            direction = decoder.update(a, b).or(direction);
+        }
+        assert_eq!(direction, Some(RotaryDirection::CounterClockwise));
+        assert_eq!(decoder.net_steps(), -1);
+    }
+
+    #[test]
+    fn invalid_two_bit_jump_is_ignored() {
+        let mut decoder = QuadratureDecoder::new();
+        // 00 -> 11 changes both bits at once, which isn't a valid quadrature
+        // transition; the table scores it 0 rather than guessing a direction.
+        assert_eq!(decoder.update(false, false), None);
+        assert_eq!(decoder.update(true, true), None);
+        assert_eq!(decoder.net_steps(), 0);
+    }
+}
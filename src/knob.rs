@@ -0,0 +1,140 @@
+//! Async alternative to the polling thread in `main`.
+//!
+//! `RotaryKnob` owns the three pins directly (no `pins()` global, no
+//! `pinqueue`) and decodes edges as they are delivered by esp-idf's
+//! interrupt-to-async bridge (`PinDriver::wait_for_any_edge`), so it can run
+//! as just another task on an executor shared with WiFi/MQTT/timers instead
+//! of a dedicated thread sleeping on a channel.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use embedded_hal::digital::v2::InputPin;
+use esp_idf_hal::gpio::{Gpio2, Gpio32, Gpio33, Input, PinDriver};
+use futures::future::{select, Either};
+use futures::stream::Stream;
+
+use crate::decoder::{QuadratureDecoder, RotaryDirection};
+use crate::RotaryKnobEvent;
+
+struct KnobState {
+    button: PinDriver<'static, Gpio2, Input>,
+    a: PinDriver<'static, Gpio33, Input>,
+    b: PinDriver<'static, Gpio32, Input>,
+    decoder: QuadratureDecoder,
+    button_pressed: bool,
+}
+
+/// Which side woke `next_event`'s outer `select`, carrying the pin level(s)
+/// read inside the arm that matches the edge future's resolution, as soon as
+/// the task is polled after the wake-up. The read is not done in the ISR, so
+/// a pin that moves again before the task runs is read at its newer level.
+enum Woke {
+    Button(bool),
+    Ab(bool, bool),
+}
+
+impl KnobState {
+    async fn next_event(&mut self) -> RotaryKnobEvent {
+        loop {
+            // Suspend until either the button or the A/B pair moves; no
+            // polling, so this composes fine alongside other async tasks.
+            // The pinned edge futures are scoped to this block so their
+            // borrows of `self` end before `woke` is matched below.
+            let woke = {
+                let a_edge = self.a.wait_for_any_edge();
+                let b_edge = self.b.wait_for_any_edge();
+                futures::pin_mut!(a_edge);
+                futures::pin_mut!(b_edge);
+                let ab_edge = select(a_edge, b_edge);
+                futures::pin_mut!(ab_edge);
+
+                let button_edge = self.button.wait_for_any_edge();
+                futures::pin_mut!(button_edge);
+
+                match select(button_edge, ab_edge).await {
+                    Either::Left(_) => Woke::Button(self.button.is_low()),
+                    Either::Right(_) => Woke::Ab(self.a.is_low(), self.b.is_low()),
+                }
+            };
+
+            match woke {
+                Woke::Button(level) => {
+                    if level != self.button_pressed {
+                        self.button_pressed = level;
+                        return if level {
+                            RotaryKnobEvent::ButtonPressed
+                        } else {
+                            RotaryKnobEvent::ButtonReleased
+                        };
+                    }
+                }
+                Woke::Ab(a, b) => {
+                    if let Some(direction) = self.decoder.update(a, b) {
+                        let net_steps = self.decoder.net_steps();
+                        return match direction {
+                            RotaryDirection::Clockwise => {
+                                RotaryKnobEvent::TurnedClockwise { net_steps }
+                            }
+                            RotaryDirection::CounterClockwise => {
+                                RotaryKnobEvent::TurnedCounterClockwise { net_steps }
+                            }
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A rotary knob (button + quadrature encoder) exposed as a `Stream` of
+/// [`RotaryKnobEvent`]s.
+///
+/// ```ignore
+/// let mut knob = RotaryKnob::new(button, a, b);
+/// while let Some(event) = knob.next().await {
+///     info!("{:?}", event);
+/// }
+/// ```
+pub struct RotaryKnob {
+    inner: Pin<Box<dyn Stream<Item = RotaryKnobEvent> + Send>>,
+}
+
+impl RotaryKnob {
+    pub fn new(
+        button: PinDriver<'static, Gpio2, Input>,
+        a: PinDriver<'static, Gpio33, Input>,
+        b: PinDriver<'static, Gpio32, Input>,
+    ) -> Self {
+        let state = KnobState {
+            button,
+            a,
+            b,
+            decoder: QuadratureDecoder::new(),
+            button_pressed: false,
+        };
+        let inner = futures::stream::unfold(state, |mut state| async move {
+            let event = state.next_event().await;
+            Some((event, state))
+        });
+        Self {
+            inner: Box::pin(inner),
+        }
+    }
+
+    /// Thin wrapper over the stream for callers that haven't moved to an
+    /// async executor yet: blocks the calling thread until the next event.
+    pub fn recv_blocking(&mut self) -> RotaryKnobEvent {
+        futures::executor::block_on(futures::stream::StreamExt::next(self)).expect(
+            "RotaryKnob's underlying stream never terminates",
+        )
+    }
+}
+
+impl Stream for RotaryKnob {
+    type Item = RotaryKnobEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
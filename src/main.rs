@@ -1,12 +1,11 @@
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Mutex;
 
 use anyhow::{anyhow, bail, Result};
 use embedded_hal::digital::v2::InputPin;
-use esp_idf_hal::gpio::{Pin, PinDriver, Pull};
+use esp_idf_hal::gpio::{Gpio2, Gpio32, Gpio33, Input, Pin, PinDriver, Pull};
 use esp_idf_hal::prelude::Peripherals;
-use esp_idf_svc::eventloop::{Background, EspEventLoop, User};
-use esp_idf_svc::systime;
+use esp_idf_hal::timer::{TimerConfig, TimerDriver};
+use esp_idf_svc::eventloop::{Background, BackgroundLoopConfiguration, EspEventLoop, User};
 use esp_idf_svc::{eventloop::EspSystemEventLoop, nvs::EspDefaultNvsPartition};
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 use esp_idf_sys::{
@@ -15,12 +14,31 @@ use esp_idf_sys::{
 };
 use log::{info, trace};
 use once_cell::sync::OnceCell;
-use rotary_encoder_hal::{Direction, Rotary};
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+mod decoder;
+// Async alternative to the blocking channel below; not wired into `main`
+// yet, but usable today from an async task via `knob::RotaryKnob`.
+#[allow(dead_code)]
+mod knob;
+mod net;
+mod pinqueue;
+
+use decoder::{QuadratureDecoder, RotaryDirection};
+use net::{KnobPublisher, NetConfig};
+use pinqueue::PinEvent;
+use serde::Serialize;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
+#[serde(tag = "event")]
 pub enum RotaryKnobEvent {
-    TurnedClockwise,
-    TurnedCounterClockwise,
+    TurnedClockwise {
+        #[serde(rename = "position")]
+        net_steps: i32,
+    },
+    TurnedCounterClockwise {
+        #[serde(rename = "position")]
+        net_steps: i32,
+    },
     ButtonPressed,
     ButtonReleased,
 }
@@ -32,19 +50,49 @@ pub enum PinId {
     B,
 }
 
+impl PinId {
+    /// Index into the per-pin debounce bookkeeping arrays.
+    fn index(self) -> usize {
+        match self {
+            PinId::Button => 0,
+            PinId::A => 1,
+            PinId::B => 2,
+        }
+    }
+}
+
+/// Lockout window after an accepted button edge: further button edges that
+/// arrive within this many microseconds of it are discarded as contact
+/// bounce. The level captured with the accepted edge is used as-is; there is
+/// no check that it then holds for this long. A/B edges are not subject to it.
+const DEBOUNCE_US: u64 = 1_500;
+
+// Compile-time network config; set these via the environment (e.g. in
+// `.cargo/config.toml`'s `[env]` section) rather than hard-coding secrets.
+const WIFI_SSID: &str = env!("WIFI_SSID");
+const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
+const MQTT_BROKER_URL: &str = env!("MQTT_BROKER_URL");
+const MQTT_TOPIC: &str = "esp32/rotary-knob";
+
 mod event {
-    use super::PinId;
+    use super::RotaryKnobEvent;
     use esp_idf_svc::eventloop::{
         EspEventFetchData, EspEventPostData, EspTypedEventDeserializer, EspTypedEventSerializer,
         EspTypedEventSource,
     };
 
+    /// A fully classified knob event plus the timestamp it was captured at,
+    /// so subscribers never need to re-read pins or touch the decoder
+    /// themselves.
     #[derive(Copy, Clone, Debug)]
-    pub struct EventLoopMessage(pub PinId);
+    pub struct EventLoopMessage {
+        pub event: RotaryKnobEvent,
+        pub ev_time: u64,
+    }
 
     impl EventLoopMessage {
-        pub fn new(data: PinId) -> Self {
-            Self(data)
+        pub fn new(event: RotaryKnobEvent, ev_time: u64) -> Self {
+            Self { event, ev_time }
         }
     }
 
@@ -73,21 +121,135 @@ mod event {
     }
 }
 
+/// Mirrors `BackgroundLoopConfiguration` so callers can size the queue for
+/// bursty rotation and pin the handler task to a specific core, instead of
+/// being stuck with `EspEventLoop`'s built-in defaults.
+#[derive(Clone, Debug)]
+pub struct EventLoopConfig {
+    pub queue_size: usize,
+    pub task_name: &'static str,
+    pub task_priority: u8,
+    pub task_stack_size: usize,
+    pub task_pin_to_core: Option<esp_idf_hal::cpu::Core>,
+}
+
+impl Default for EventLoopConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 64,
+            task_name: "knob_event_loop",
+            task_priority: 5,
+            // Above esp-idf's own background-loop default: the subscriber
+            // now does serde_json::to_vec plus an MQTT publish on this task,
+            // which needs more headroom than a bare event dispatch.
+            task_stack_size: 6144,
+            task_pin_to_core: None,
+        }
+    }
+}
+
+impl From<EventLoopConfig> for BackgroundLoopConfiguration<'static> {
+    fn from(config: EventLoopConfig) -> Self {
+        BackgroundLoopConfiguration {
+            queue_size: config.queue_size,
+            task_name: config.task_name,
+            task_priority: config.task_priority,
+            task_stack_size: config.task_stack_size,
+            task_pin_to_core: config.task_pin_to_core,
+            ..Default::default()
+        }
+    }
+}
+
+static EVENT_LOOP: OnceCell<Mutex<EspEventLoop<User<Background>>>> = OnceCell::new();
+
 fn event_loop() -> &'static Mutex<EspEventLoop<User<Background>>> {
-    static INSTANCE: OnceCell<Mutex<EspEventLoop<User<Background>>>> = OnceCell::new();
-    INSTANCE.get_or_init(|| {
-        #[allow(unused)]
-        let eventloop = EspEventLoop::<User<Background>>::new(&Default::default()).unwrap();
-        Mutex::new(eventloop)
+    EVENT_LOOP
+        .get()
+        .expect("event_loop() accessed before init_event_loop()")
+}
+
+/// Installs the global event loop with the given configuration. Must be
+/// called exactly once from `main` before anything posts or subscribes.
+fn init_event_loop(config: EventLoopConfig) {
+    let background_config: BackgroundLoopConfiguration = config.into();
+    EVENT_LOOP
+        .set(Mutex::new(
+            EspEventLoop::<User<Background>>::new(&background_config).unwrap(),
+        ))
+        .ok()
+        .expect("init_event_loop called more than once");
+}
+
+/// The GPIO pins plus the free-running timer used to stamp edges, bundled so
+/// the ISR closures (which must be `'static` and can't borrow locals in
+/// `main`) can reach them through a single global. Never wrapped in a
+/// `Mutex`: every field is only ever read (`is_low`/`counter` take `&self`)
+/// once `subscribe` has been called on the locals, so ISRs and the drain
+/// thread can share `&'static Pins` without a lock.
+struct Pins {
+    button: PinDriver<'static, Gpio2, Input>,
+    a: PinDriver<'static, Gpio33, Input>,
+    b: PinDriver<'static, Gpio32, Input>,
+    timer: TimerDriver<'static>,
+}
+
+static PINS: OnceCell<Pins> = OnceCell::new();
+
+fn pins() -> &'static Pins {
+    PINS.get().expect("pins() accessed before init_pins()")
+}
+
+/// Installs the global [`Pins`] instance. Must be called exactly once from
+/// `main`, after the pins have been `subscribe`d (which needs `&mut`); an edge
+/// that fires in between is dropped by [`handle_interrupt`].
+fn init_pins(
+    button: PinDriver<'static, Gpio2, Input>,
+    a: PinDriver<'static, Gpio33, Input>,
+    b: PinDriver<'static, Gpio32, Input>,
+    timer: TimerDriver<'static>,
+) {
+    PINS.set(Pins {
+        button,
+        a,
+        b,
+        timer,
     })
+    .ok()
+    .expect("init_pins called more than once");
 }
 
-fn handle_interrupt(pin_id: PinId) {
-    event_loop()
-        .lock()
-        .unwrap()
-        .post(&event::EventLoopMessage::new(pin_id), None)
-        .unwrap();
+/// The decoder needs `&mut self` to advance, so it lives behind its own
+/// `Mutex`. Only the (non-ISR) drain thread ever touches it.
+static DECODER: OnceCell<Mutex<QuadratureDecoder>> = OnceCell::new();
+
+fn decoder() -> &'static Mutex<QuadratureDecoder> {
+    DECODER.get_or_init(|| Mutex::new(QuadratureDecoder::new()))
+}
+
+/// Captures the current level of `pin` and the free-running timer reading,
+/// then hands the pair off to the lock-free queue. Runs in interrupt
+/// context: no allocation and no lock -- `pins()` is read-only, and
+/// `pinqueue::push` only ever masks interrupts briefly, never blocking on
+/// the `event_loop()` post that this replaces.
+///
+/// The pins are subscribed (arming the interrupt) before `init_pins` runs,
+/// so an edge can in principle reach this function before `PINS` is set;
+/// silently drop it rather than panicking in interrupt context, matching
+/// how `pinqueue::push` already tolerates running before `init`.
+fn handle_interrupt(pin: PinId) {
+    let Some(guard) = PINS.get() else { return };
+    let level = match pin {
+        PinId::Button => guard.button.is_low(),
+        PinId::A => guard.a.is_low(),
+        PinId::B => guard.b.is_low(),
+    };
+    let ev_time = guard.timer.counter().unwrap_or(0);
+    pinqueue::push(PinEvent {
+        pin,
+        level,
+        ev_time,
+    });
 }
 
 fn main() {
@@ -112,6 +274,24 @@ fn main() {
     let sys_loop = EspSystemEventLoop::take().unwrap();
     let nvs = EspDefaultNvsPartition::take().unwrap();
 
+    let net_config = NetConfig {
+        wifi_ssid: WIFI_SSID,
+        wifi_password: WIFI_PASSWORD,
+        mqtt_broker_url: MQTT_BROKER_URL,
+        mqtt_topic: MQTT_TOPIC,
+    };
+    let _wifi = net::connect_wifi(peripherals.modem, sys_loop.clone(), nvs.clone(), &net_config)
+        .expect("wifi connect failed");
+    let mut publisher = KnobPublisher::new(&net_config).expect("mqtt client init failed");
+
+    // Free-running microsecond counter shared by every ISR for debounce
+    // timestamps. No alarms are configured; it is only ever read.
+    let mut timer = TimerDriver::new(peripherals.timer00, &TimerConfig::new()).unwrap();
+    timer.enable(true).unwrap();
+
+    // Set up before any pin is subscribed, so no ISR can race `init`.
+    let consumer = pinqueue::init();
+
     let mut io2 = PinDriver::input(peripherals.pins.gpio2).unwrap();
     let mut io32 = PinDriver::input(peripherals.pins.gpio32).unwrap();
     let mut io33 = PinDriver::input(peripherals.pins.gpio33).unwrap();
@@ -127,62 +307,112 @@ fn main() {
     io33.set_interrupt_type(esp_idf_hal::gpio::InterruptType::AnyEdge)
         .unwrap();
 
+    // Subscribe on the locals (`subscribe` needs `&mut self`) before they
+    // move into the `Pins` global, which only ever hands out `&Pins`.
     unsafe {
         io2.subscribe(|| handle_interrupt(PinId::Button)).unwrap();
         io32.subscribe(|| handle_interrupt(PinId::B)).unwrap();
         io33.subscribe(|| handle_interrupt(PinId::A)).unwrap();
     }
-    // Moved into closure
-    let mut enc = Rotary::new(io33, io32);
-    let mut button_pressed = false;
-    let systime = esp_idf_svc::systime::EspSystemTime;
-    let mut prev_time = systime.now();
-    // TODO try async?
-    let (tx, rx): (Sender<RotaryKnobEvent>, Receiver<RotaryKnobEvent>) = std::sync::mpsc::channel();
 
+    init_pins(io2, io33, io32, timer);
+
+    // Sized for bursty rotation and left unpinned; callers with tighter
+    // latency needs can build their own `EventLoopConfig` instead.
+    init_event_loop(EventLoopConfig::default());
+
+    // Subscriber just does I/O (logging, MQTT) against an already-classified
+    // event -- no pins or decoder state to touch here any more.
     let sub = event_loop()
         .lock()
         .unwrap()
-        .subscribe(move |e: &event::EventLoopMessage| {
-            trace!("Received event: {:?}", e);
-            let new_time = systime.now();
-            if new_time - prev_time < core::time::Duration::from_millis(100) {
-                trace!("Discarding event due to rapid timing: {:?}", e);
-                enc.update().unwrap();
-                return;
+        .subscribe(move |msg: &event::EventLoopMessage| {
+            info!("Received RotaryKnob event: {:?}", msg.event);
+            publisher.publish(msg.event, msg.ev_time);
+        })
+        .unwrap();
+
+    let mut button_pressed = false;
+
+    // Drains the lock-free queue and turns raw edges (button edges debounced) into
+    // classified `RotaryKnobEvent`s, posted to the event loop above. Lives on
+    // its own thread so ISR latency never depends on subscriber work.
+    std::thread::spawn(move || {
+        let mut consumer = consumer;
+        let mut last_edge_time = [0u64; 3];
+        // Last level observed on each pin, fed to the decoder from the
+        // ISR-captured edges themselves rather than a live re-read: by the
+        // time this thread gets around to dequeuing, the pins may already
+        // have moved on, which would turn a real step into a spurious
+        // "both bits changed" transition the decoder has to discard.
+        let mut a_level = false;
+        let mut b_level = false;
+        loop {
+            let Some(ev) = consumer.dequeue() else {
+                std::thread::sleep(std::time::Duration::from_micros(200));
+                continue;
+            };
+            // The debounce lockout only applies to the button: for A/B it
+            // would drop a real quarter-step at high turn rates and hand the
+            // decoder an invalid two-bit jump on the next accepted edge
+            // instead. The decoder already rejects bounce on A/B itself (any
+            // transition that isn't a valid single-bit change scores 0), so
+            // every captured A/B level is recorded unconditionally.
+            if ev.pin == PinId::Button {
+                let idx = ev.pin.index();
+                if ev.ev_time.saturating_sub(last_edge_time[idx]) < DEBOUNCE_US {
+                    trace!("Discarding bouncing button edge: {:?}", ev);
+                    continue;
+                }
+                last_edge_time[idx] = ev.ev_time;
             }
-            prev_time = new_time;
-            match e.0 {
+
+            let maybe_event = match ev.pin {
                 PinId::A | PinId::B => {
-                    let maybe_event = match enc.update().unwrap() {
-                        Direction::Clockwise => Some(RotaryKnobEvent::TurnedClockwise),
-                        Direction::CounterClockwise => {
-                            Some(RotaryKnobEvent::TurnedCounterClockwise)
-                        }
-                        Direction::None => None,
-                    };
-                    if let Some(event) = maybe_event {
-                        tx.send(event).unwrap();
+                    if ev.pin == PinId::A {
+                        a_level = ev.level;
+                    } else {
+                        b_level = ev.level;
                     }
+                    let mut decoder = decoder().lock().unwrap();
+                    decoder.update(a_level, b_level).map(|direction| {
+                        let net_steps = decoder.net_steps();
+                        match direction {
+                            RotaryDirection::Clockwise => {
+                                RotaryKnobEvent::TurnedClockwise { net_steps }
+                            }
+                            RotaryDirection::CounterClockwise => {
+                                RotaryKnobEvent::TurnedCounterClockwise { net_steps }
+                            }
+                        }
+                    })
                 }
                 PinId::Button => {
-                    let c = io2.is_low();
-                    if button_pressed != c {
-                        button_pressed = c;
-                        if button_pressed {
-                            tx.send(RotaryKnobEvent::ButtonPressed).unwrap();
+                    if button_pressed != ev.level {
+                        button_pressed = ev.level;
+                        Some(if button_pressed {
+                            RotaryKnobEvent::ButtonPressed
                         } else {
-                            tx.send(RotaryKnobEvent::ButtonReleased).unwrap();
-                        }
+                            RotaryKnobEvent::ButtonReleased
+                        })
+                    } else {
+                        None
                     }
                 }
+            };
+
+            if let Some(event) = maybe_event {
+                event_loop()
+                    .lock()
+                    .unwrap()
+                    .post(&event::EventLoopMessage::new(event, ev.ev_time), None)
+                    .unwrap();
             }
-        })
-        .unwrap();
+        }
+    });
 
     info!("Entering loop...");
     loop {
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        info!("Received RotaryKnob event: {:?}", rx.recv().unwrap());
+        std::thread::sleep(std::time::Duration::from_secs(60));
     }
 }
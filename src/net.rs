@@ -0,0 +1,113 @@
+//! Optional WiFi + MQTT bring-up so knob events can drive a remote system.
+//!
+//! Connects once at startup and publishes each [`RotaryKnobEvent`] as JSON.
+//! Publishing never blocks the caller on the network: the underlying
+//! `EspMqttClient` queues outgoing messages and reconnects on its own, and a
+//! publish that still fails (queue full, not yet connected) is logged and
+//! dropped rather than retried inline.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use embedded_svc::mqtt::client::QoS;
+use esp_idf_hal::modem::Modem;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::RotaryKnobEvent;
+
+/// Network settings; currently compiled in, but kept as one struct so the
+/// values can move to NVS-backed config without touching the call sites.
+pub struct NetConfig {
+    pub wifi_ssid: &'static str,
+    pub wifi_password: &'static str,
+    pub mqtt_broker_url: &'static str,
+    pub mqtt_topic: &'static str,
+}
+
+#[derive(Serialize)]
+struct KnobEventPayload {
+    ts: u64,
+    #[serde(flatten)]
+    event: RotaryKnobEvent,
+}
+
+/// Brings up STA-mode WiFi and blocks until connected. Returns the
+/// `BlockingWifi` wrapper itself -- callers only need to keep it alive to
+/// hold the connection up; `BlockingWifi` has no `into_driver` to unwrap to
+/// the bare `EspWifi`, and none of the call sites need the inner driver
+/// directly.
+pub fn connect_wifi(
+    modem: Modem,
+    sys_loop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
+    config: &NetConfig,
+) -> Result<Box<BlockingWifi<EspWifi<'static>>>> {
+    let mut wifi = BlockingWifi::wrap(
+        EspWifi::new(modem, sys_loop.clone(), Some(nvs))?,
+        sys_loop,
+    )?;
+
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: config.wifi_ssid.try_into().unwrap(),
+        password: config.wifi_password.try_into().unwrap(),
+        auth_method: AuthMethod::WPA2Personal,
+        ..Default::default()
+    }))?;
+
+    wifi.start()?;
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+
+    info!("WiFi connected, SSID: {}", config.wifi_ssid);
+    Ok(Box::new(wifi))
+}
+
+/// Publishes classified [`RotaryKnobEvent`]s to a configured MQTT topic.
+pub struct KnobPublisher {
+    client: EspMqttClient<'static>,
+    topic: String,
+}
+
+impl KnobPublisher {
+    pub fn new(config: &NetConfig) -> Result<Self> {
+        let client = EspMqttClient::new_cb(
+            config.mqtt_broker_url,
+            &MqttClientConfiguration {
+                client_id: Some("esp32-rotary-knob"),
+                keep_alive_interval: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            |_event| {},
+        )?;
+
+        Ok(Self {
+            client,
+            topic: config.mqtt_topic.to_string(),
+        })
+    }
+
+    /// Serializes and publishes `event`. Drops the event (with a warning)
+    /// rather than blocking if the broker is unreachable or the client's
+    /// outgoing queue is full.
+    pub fn publish(&mut self, event: RotaryKnobEvent, ts: u64) {
+        let payload = match serde_json::to_vec(&KnobEventPayload { ts, event }) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize knob event {:?}: {}", event, e);
+                return;
+            }
+        };
+
+        if let Err(e) = self
+            .client
+            .enqueue(&self.topic, QoS::AtMostOnce, false, &payload)
+        {
+            warn!("Dropping knob event {:?}, MQTT publish failed: {}", event, e);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Lock-free ISR-to-thread handoff for raw GPIO edges.
+//!
+//! `PinDriver::subscribe` closures run in interrupt context, so they must not
+//! take a FreeRTOS-backed `std::sync::Mutex` or otherwise block. Instead each
+//! ISR stamps the edge with the current microsecond counter and pushes it
+//! onto a `heapless::spsc::Queue`, which is single-producer by construction
+//! and safe to enqueue from an interrupt and drain from a regular thread.
+
+use heapless::spsc::{Consumer, Producer, Queue};
+
+use crate::PinId;
+
+/// Size of the backing queue; heapless's spsc queue keeps one slot free, so
+/// it holds `QUEUE_CAPACITY - 1` in-flight edges before the ISR side starts
+/// dropping them. Sized generously above the fastest realistic turn rate.
+const QUEUE_CAPACITY: usize = 32;
+
+/// A single GPIO edge as observed by an ISR, stamped with the hardware timer
+/// reading at the moment of capture.
+#[derive(Copy, Clone, Debug)]
+pub struct PinEvent {
+    pub pin: PinId,
+    pub level: bool,
+    pub ev_time: u64,
+}
+
+// There is only ever one producer (set up once by `init`, before interrupts
+// are enabled) and the three GPIO ISRs that call `push` never run nested on
+// top of each other on the same core, so a brief interrupt-masking critical
+// section is enough to serialize access -- no blocking FreeRTOS primitive
+// needed.
+static mut PRODUCER: Option<Producer<'static, PinEvent, QUEUE_CAPACITY>> = None;
+
+/// Sets up the backing queue and returns the consumer half.
+///
+/// Must be called exactly once before any ISR pushes a [`PinEvent`]; call it
+/// from `main` before `subscribe`-ing the GPIO pins.
+pub fn init() -> Consumer<'static, PinEvent, QUEUE_CAPACITY> {
+    static mut QUEUE: Queue<PinEvent, QUEUE_CAPACITY> = Queue::new();
+    // Safety: `init` is only ever called once from `main`, before interrupts
+    // are enabled on the subscribed pins, so this is the sole access to
+    // `QUEUE` and `PRODUCER` at this point.
+    unsafe {
+        let (producer, consumer) = QUEUE.split();
+        PRODUCER = Some(producer);
+        consumer
+    }
+}
+
+/// Pushes a captured edge onto the queue. Safe to call from ISR context.
+///
+/// Silently drops the event if the queue is full or `init` has not run yet,
+/// since an ISR has no way to report back a missed push.
+pub fn push(event: PinEvent) {
+    esp_idf_hal::interrupt::free(|| {
+        // Safety: interrupts are disabled for the duration of this closure,
+        // so no other ISR can be touching `PRODUCER` concurrently on this
+        // core, matching spsc's single-producer contract without a lock.
+        unsafe {
+            if let Some(producer) = PRODUCER.as_mut() {
+                let _ = producer.enqueue(event);
+            }
+        }
+    });
+}